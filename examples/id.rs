@@ -1,30 +1,75 @@
 use env_logger;
 use log::debug;
+use std::time::Duration;
 
+use crossbeam_channel::unbounded;
 use futures::{future::lazy, prelude::*};
-use identify::{Callback, IdentifyProtocol, MisbehaveResult, Misbehavior};
+use identify::{
+    capability::CompressionNegotiation,
+    gate::{GatedProtocol, IdentifyGate},
+    Callback, Event, IdentifyProtocol, MisbehaveResult, Misbehavior,
+};
 use tentacle::{
     builder::{MetaBuilder, ServiceBuilder},
-    context::{ProtocolContextMutRef, ServiceContext},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
     multiaddr::Multiaddr,
     secio::{PeerId, SecioKeyPair},
     service::{DialProtocol, ProtocolHandle, ServiceError, ServiceEvent, SessionType},
-    traits::ServiceHandle,
+    traits::{ServiceHandle, ServiceProtocol},
 };
 
 fn main() {
     env_logger::init();
+
+    // Sessions start out unidentified; `DiscoveryStub` below only opens once an
+    // `Event::Identified` for that session comes back through `event_receiver`.
+    let gate = IdentifyGate::new();
+    let (event_sender, event_receiver) = unbounded::<Event>();
+    {
+        let gate = gate.clone();
+        std::thread::spawn(move || {
+            for event in event_receiver {
+                gate.handle_event(&event);
+            }
+        });
+    }
+
     let callback = IdentifyCallback {
+        chain_id: b"ckb-mainnet".to_vec(),
         local_listen_addrs: Vec::new(),
     };
-    let protocol = MetaBuilder::default()
-        .id(1.into())
-        .service_handle(move || ProtocolHandle::Callback(Box::new(IdentifyProtocol::new(callback))))
+    let compression_negotiation = CompressionNegotiation::new();
+    let identify_protocol = {
+        let compression_negotiation = compression_negotiation.clone();
+        MetaBuilder::default()
+            .id(1.into())
+            .service_handle(move || {
+                ProtocolHandle::Callback(Box::new(IdentifyProtocol::new(
+                    callback,
+                    Duration::from_secs(10),
+                    event_sender,
+                    compression_negotiation.clone(),
+                )))
+            })
+            .build()
+    };
+
+    let discovery_gate = gate;
+    let discovery_protocol = MetaBuilder::default()
+        .id(2.into())
+        .service_handle(move || {
+            ProtocolHandle::Callback(Box::new(GatedProtocol::new(
+                DiscoveryStub,
+                discovery_gate.clone(),
+            )))
+        })
         .build();
+
     if std::env::args().nth(1) == Some("server".to_string()) {
         debug!("Starting server ......");
         let mut service = ServiceBuilder::default()
-            .insert_protocol(protocol)
+            .insert_protocol(identify_protocol)
+            .insert_protocol(discovery_protocol)
             .key_pair(SecioKeyPair::secp256k1_generated())
             .forever(true)
             .build(SimpleHandler {});
@@ -37,7 +82,8 @@ fn main() {
     } else {
         debug!("Starting client ......");
         let mut service = ServiceBuilder::default()
-            .insert_protocol(protocol)
+            .insert_protocol(identify_protocol)
+            .insert_protocol(discovery_protocol)
             .key_pair(SecioKeyPair::secp256k1_generated())
             .forever(true)
             .build(SimpleHandler {});
@@ -52,12 +98,29 @@ fn main() {
 
 #[derive(Clone)]
 struct IdentifyCallback {
+    // Network this node belongs to; peers that report a different chain_id during
+    // identify are refused so a foreign-network peer never gets to discovery.
+    chain_id: Vec<u8>,
     local_listen_addrs: Vec<Multiaddr>,
 }
 
 impl Callback for IdentifyCallback {
+    fn chain_id(&self) -> &[u8] {
+        &self.chain_id
+    }
+
     fn identify(&mut self) -> &[u8] {
-        "Identify message".as_bytes()
+        b"Identify message"
+    }
+
+    fn supports_snappy(&self) -> bool {
+        true
+    }
+
+    fn verify_chain_id(&mut self, _remote_chain_id: &[u8]) -> MisbehaveResult {
+        // `IdentifyProtocol::received` already disconnects on a straightforward mismatch
+        // against `chain_id()`; nothing additional to veto on here.
+        MisbehaveResult::Continue
     }
 
     fn received_identify(
@@ -90,6 +153,28 @@ impl Callback for IdentifyCallback {
     }
 }
 
+/// Stands in for the real discovery protocol: it only exists to demonstrate that
+/// `GatedProtocol` keeps a protocol closed until its session has passed identify.
+#[derive(Clone)]
+struct DiscoveryStub;
+
+impl ServiceProtocol for DiscoveryStub {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        debug!(
+            "discovery opened on session [{}] (only reachable post-identify)",
+            context.session.id
+        );
+    }
+
+    fn disconnected(&mut self, _context: ProtocolContextMutRef) {}
+
+    fn received(&mut self, _context: ProtocolContextMutRef, _data: bytes::Bytes) {}
+
+    fn notify(&mut self, _context: &mut ProtocolContext, _token: u64) {}
+}
+
 struct SimpleHandler {}
 
 impl ServiceHandle for SimpleHandler {
@@ -0,0 +1,71 @@
+/// Per-session capabilities negotiated during the identify handshake, alongside the chain
+/// ID check in `crate::lib`. Right now this is just Snappy frame compression support
+/// (`protocols/ping` and friends consult [`CompressionNegotiation::supports_snappy`] before
+/// compressing a frame for a given session), but the shape generalizes to other
+/// identify-time capability bits.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use p2p::SessionId;
+
+/// Shared, thread-safe record of which sessions advertised Snappy support during identify.
+/// Cheap to clone (an `Arc`) so `IdentifyProtocol` and every protocol that wants to compress
+/// frames for a session can share one.
+#[derive(Clone, Default)]
+pub struct CompressionNegotiation {
+    snappy_supported: Arc<Mutex<HashSet<SessionId>>>,
+}
+
+impl CompressionNegotiation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `session_id`'s peer advertised Snappy support in its identify frame.
+    pub fn mark_supports_snappy(&self, session_id: SessionId) {
+        self.snappy_supported.lock().unwrap().insert(session_id);
+    }
+
+    /// Whether `session_id`'s peer has advertised Snappy support. A protocol should only
+    /// compress outbound frames for a session once this is true *and* its own local
+    /// configuration wants compression — this only tells you what the peer can accept.
+    pub fn supports_snappy(&self, session_id: SessionId) -> bool {
+        self.snappy_supported.lock().unwrap().contains(&session_id)
+    }
+
+    /// Called once a session closes, so this doesn't leak entries.
+    pub fn session_closed(&self, session_id: SessionId) {
+        self.snappy_supported.lock().unwrap().remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_id(n: usize) -> SessionId {
+        n.into()
+    }
+
+    #[test]
+    fn test_unsupported_by_default() {
+        let negotiation = CompressionNegotiation::new();
+        assert!(!negotiation.supports_snappy(session_id(1)));
+    }
+
+    #[test]
+    fn test_marking_support_is_observed() {
+        let negotiation = CompressionNegotiation::new();
+        negotiation.mark_supports_snappy(session_id(1));
+        assert!(negotiation.supports_snappy(session_id(1)));
+        assert!(!negotiation.supports_snappy(session_id(2)));
+    }
+
+    #[test]
+    fn test_session_closed_clears_support() {
+        let negotiation = CompressionNegotiation::new();
+        negotiation.mark_supports_snappy(session_id(1));
+        negotiation.session_closed(session_id(1));
+        assert!(!negotiation.supports_snappy(session_id(1)));
+    }
+}
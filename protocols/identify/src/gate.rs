@@ -0,0 +1,205 @@
+/// The "identified" gate: other protocols (ping, discovery, etc.) must not be opened on a
+/// session until identify has completed and the chain ID matched. [`IdentifyGate`] tracks
+/// which sessions have reached that state from the [`crate::Event`] stream `IdentifyProtocol`
+/// emits, and [`GatedProtocol`] wraps any `ServiceProtocol` so its `connected`/`received`
+/// hooks are suppressed for a session until the gate says it's identified.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use p2p::{
+    context::{ProtocolContext, ProtocolContextMutRef},
+    traits::ServiceProtocol,
+    SessionId,
+};
+
+use crate::Event;
+
+/// Shared, thread-safe record of which sessions have completed identify. Cheap to clone
+/// (an `Arc`), so the same gate can be handed to `IdentifyProtocol`'s event consumer and to
+/// every `GatedProtocol` that should wait on it.
+#[derive(Clone, Default)]
+pub struct IdentifyGate {
+    identified: Arc<Mutex<HashSet<SessionId>>>,
+}
+
+impl IdentifyGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds an `identify::Event` into the gate; call this from wherever consumes the
+    /// `Sender<Event>`/`Receiver<Event>` channel `IdentifyProtocol` was built with (the same
+    /// place that would consume `ping::Event`).
+    pub fn handle_event(&self, event: &Event) {
+        let mut identified = self.identified.lock().unwrap();
+        match event {
+            Event::Identified(session_id) => {
+                identified.insert(*session_id);
+            }
+            Event::Timeout(session_id) | Event::DifferentChainId(session_id) => {
+                identified.remove(session_id);
+            }
+        }
+    }
+
+    /// Whether `session_id` has completed identify and other protocols may open on it.
+    ///
+    /// This is the "wait" primitive the request asks for (`wait_identified(SessionId)`): in
+    /// this synchronous `ServiceProtocol` callback style, "waiting" means the gate keeps
+    /// saying no (and `GatedProtocol` keeps refusing to forward `connected`/`received`)
+    /// until an `Event::Identified` for that session arrives; there is no separate future
+    /// to poll.
+    pub fn is_identified(&self, session_id: SessionId) -> bool {
+        self.identified.lock().unwrap().contains(&session_id)
+    }
+
+    /// Called once a session closes, so the gate doesn't leak entries.
+    pub fn session_closed(&self, session_id: SessionId) {
+        self.identified.lock().unwrap().remove(&session_id);
+    }
+}
+
+/// Wraps a `ServiceProtocol` so it only sees `connected`/`received` for sessions the
+/// `IdentifyGate` has marked identified, keeping a foreign-network or not-yet-identified peer
+/// from ever reaching this protocol.
+///
+/// `ServiceProtocol::connected` fires exactly once per session (the same assumption the rest
+/// of this codebase relies on, e.g. `PingHandler::connected`'s `entry().or_insert_with`), and
+/// in a real deployment it fires at protocol-open time — before identify's one round trip has
+/// had a chance to complete. So a session that isn't identified yet when `connected()` runs
+/// can't just have that call dropped: `inner` would never get a second chance to initialize
+/// its per-session state, and would stay dead for that session forever even after it
+/// identifies. Instead, `GatedProtocol` buffers the suppressed connect and replays it into
+/// `inner.connected()` the moment `received()` next sees that session past the gate.
+///
+/// This still leaves a gap for an inner protocol that only ever pushes data from `notify()`
+/// and never receives anything: if such a session never sends another frame on this protocol
+/// after identifying, the buffered connect is never replayed and `inner` never opens for it.
+/// Closing that gap for real means not opening the substream at all until identify completes
+/// (a service/negotiation-layer change), which needs API surface from the `p2p`/`tentacle`
+/// crate that isn't part of this trimmed source tree.
+pub struct GatedProtocol<P> {
+    inner: P,
+    gate: IdentifyGate,
+    /// Sessions whose `connected()` was suppressed because they weren't identified yet, keyed
+    /// by the version string the framework passed at connect time so it can be replayed later.
+    pending_connects: HashMap<SessionId, String>,
+    /// Sessions for which `inner.connected()` has actually run (either immediately or via a
+    /// replay), so `disconnected()` only ever forwards for a session `inner` actually opened.
+    opened: HashSet<SessionId>,
+}
+
+impl<P: ServiceProtocol> GatedProtocol<P> {
+    pub fn new(inner: P, gate: IdentifyGate) -> Self {
+        GatedProtocol {
+            inner,
+            gate,
+            pending_connects: HashMap::new(),
+            opened: HashSet::new(),
+        }
+    }
+}
+
+impl<P: ServiceProtocol> ServiceProtocol for GatedProtocol<P> {
+    fn init(&mut self, context: &mut ProtocolContext) {
+        self.inner.init(context)
+    }
+
+    fn connected(&mut self, context: ProtocolContextMutRef, version: &str) {
+        let session_id = context.session.id;
+        if self.gate.is_identified(session_id) {
+            self.inner.connected(context, version);
+            self.opened.insert(session_id);
+        } else {
+            self.pending_connects
+                .insert(session_id, version.to_string());
+        }
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        let session_id = context.session.id;
+        self.gate.session_closed(session_id);
+        self.pending_connects.remove(&session_id);
+        if self.opened.remove(&session_id) {
+            self.inner.disconnected(context);
+        }
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, data: Bytes) {
+        let session_id = context.session.id;
+        if let Some(version) = self.pending_connects.remove(&session_id) {
+            if self.gate.is_identified(session_id) {
+                // The session identified since `connected()` was suppressed: replay it now so
+                // `inner`'s per-session state exists before we'd otherwise forward anything to
+                // it. We only have one owned `context` to spend on either call, so this frame
+                // is dropped alongside the replay rather than forwarded too — same as any
+                // other frame that arrives before a session identifies.
+                self.inner.connected(context, &version);
+                self.opened.insert(session_id);
+            } else {
+                self.pending_connects.insert(session_id, version);
+            }
+            return;
+        }
+        if self.opened.contains(&session_id) {
+            self.inner.received(context, data);
+        }
+    }
+
+    fn notify(&mut self, context: &mut ProtocolContext, token: u64) {
+        self.inner.notify(context, token)
+    }
+}
+
+// Only `IdentifyGate` gets direct tests below: exercising `GatedProtocol::connected`/
+// `received` needs a real `ProtocolContextMutRef`/`SessionContext`, and those are opaque
+// types from the `p2p`/`tentacle` crate that isn't part of this trimmed source tree (the
+// same constraint `secio::rotation`/`secio::crypto_pool` are upfront about), so there's no
+// way to construct one here to drive a fake stateful `ServiceProtocol` through a
+// connect-before-identify sequence.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_id(n: usize) -> SessionId {
+        n.into()
+    }
+
+    #[test]
+    fn test_session_unidentified_by_default() {
+        let gate = IdentifyGate::new();
+        assert!(!gate.is_identified(session_id(1)));
+    }
+
+    #[test]
+    fn test_identified_event_opens_the_gate() {
+        let gate = IdentifyGate::new();
+        gate.handle_event(&Event::Identified(session_id(1)));
+        assert!(gate.is_identified(session_id(1)));
+    }
+
+    #[test]
+    fn test_timeout_event_keeps_gate_closed() {
+        let gate = IdentifyGate::new();
+        gate.handle_event(&Event::Identified(session_id(1)));
+        gate.handle_event(&Event::Timeout(session_id(1)));
+        assert!(!gate.is_identified(session_id(1)));
+    }
+
+    #[test]
+    fn test_different_chain_id_revokes_identified() {
+        let gate = IdentifyGate::new();
+        gate.handle_event(&Event::Identified(session_id(2)));
+        gate.handle_event(&Event::DifferentChainId(session_id(2)));
+        assert!(!gate.is_identified(session_id(2)));
+    }
+
+    #[test]
+    fn test_session_closed_clears_gate() {
+        let gate = IdentifyGate::new();
+        gate.handle_event(&Event::Identified(session_id(3)));
+        gate.session_closed(session_id(3));
+        assert!(!gate.is_identified(session_id(3)));
+    }
+}
@@ -0,0 +1,335 @@
+/// Identify protocol: right after a session opens, both sides exchange a chain (network) ID
+/// plus an application-level identify payload. A session whose chain ID doesn't match ours
+/// is disconnected before it ever sees `received_identify`'s payload, and a session that
+/// never completes identify within `identify_timeout` is disconnected too. See
+/// [`gate::IdentifyGate`] for how other protocols (ping, discovery, etc.) consult this to
+/// stay closed until a session has identified. The same frame also negotiates a
+/// `compression: none|snappy` capability; see [`capability::CompressionNegotiation`].
+pub mod capability;
+pub mod gate;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use generic_channel::Sender;
+use log::{debug, error, warn};
+use p2p::{
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::PeerId,
+    service::SessionType,
+    traits::ServiceProtocol,
+    SessionId,
+};
+
+use crate::capability::CompressionNegotiation;
+
+const CHECK_TIMEOUT_TOKEN: u64 = 0;
+
+/// Capability bit flags carried in the frame's single capability byte.
+const CAPABILITY_SNAPPY: u8 = 0b0000_0001;
+
+/// What a `Callback` hook decided to do about the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisbehaveResult {
+    /// Keep the session open.
+    Continue,
+    /// Tear the session down.
+    Disconnect,
+}
+
+/// Ways a peer can misbehave during identify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// The peer advertised a chain ID other than ours.
+    DifferentChainId,
+    /// The peer didn't finish identify before `identify_timeout` elapsed.
+    IdentifyTimeout,
+    /// The identify frame didn't parse.
+    InvalidData,
+}
+
+/// Events emitted as sessions identify, so the service can drive [`gate::IdentifyGate`]
+/// the same way `ping::Event` drives `PingHandler`'s callers.
+#[derive(Debug)]
+pub enum Event {
+    /// `SessionId` completed identify and its chain ID matched ours.
+    Identified(SessionId),
+    /// `SessionId` stayed unidentified past `identify_timeout` and was disconnected.
+    Timeout(SessionId),
+    /// `SessionId` advertised a different chain ID and was disconnected.
+    DifferentChainId(SessionId),
+}
+
+/// Hooks the identify protocol calls into as sessions identify.
+pub trait Callback: Clone + Send {
+    /// Our own chain (network) ID, advertised to the peer.
+    fn chain_id(&self) -> &[u8];
+
+    /// Application-level identify payload to send, in addition to the chain ID.
+    fn identify(&mut self) -> &[u8];
+
+    /// Whether we are willing to receive Snappy-compressed frames on protocols that support
+    /// it (e.g. ping). Advertised to the peer in the identify frame; a protocol should only
+    /// compress outbound frames for a session once the peer has advertised this, which
+    /// `CompressionNegotiation::supports_snappy` tracks.
+    fn supports_snappy(&self) -> bool {
+        false
+    }
+
+    /// Checks a remote's advertised chain ID against ours. `IdentifyProtocol::received`
+    /// already disconnects on a straightforward mismatch; this lets callbacks veto for
+    /// additional reasons (e.g. a chain ID allowlist).
+    fn verify_chain_id(&mut self, remote_chain_id: &[u8]) -> MisbehaveResult;
+
+    /// Called with the peer's application-level identify payload, once its chain ID matched.
+    fn received_identify(
+        &mut self,
+        context: &mut ProtocolContextMutRef,
+        identify: &[u8],
+    ) -> MisbehaveResult;
+
+    /// Get local listen addresses.
+    fn local_listen_addrs(&mut self) -> Vec<Multiaddr>;
+    /// Add remote peer's listen addresses.
+    fn add_remote_listen_addrs(&mut self, peer: &PeerId, addrs: Vec<Multiaddr>);
+    /// Add our address observed by remote peer.
+    fn add_observed_addr(
+        &mut self,
+        peer: &PeerId,
+        addr: Multiaddr,
+        ty: SessionType,
+    ) -> MisbehaveResult;
+    /// Report misbehavior.
+    fn misbehave(&mut self, peer: &PeerId, kind: Misbehavior) -> MisbehaveResult;
+}
+
+struct RemoteInfo {
+    peer_id: PeerId,
+    identified: bool,
+    connected_at: Instant,
+}
+
+/// Identify protocol handler: wire format is a 4-byte big-endian chain-ID length, the chain
+/// ID itself, a single capability byte, then the application-level identify payload.
+pub struct IdentifyProtocol<T: Callback, S: Sender<Event>> {
+    callback: T,
+    identify_timeout: Duration,
+    remote_infos: HashMap<SessionId, RemoteInfo>,
+    event_sender: S,
+    /// Negotiated Snappy compression support, shared with whichever protocols want to
+    /// compress frames (e.g. `ping::PingHandler::with_compression_negotiation`).
+    compression: CompressionNegotiation,
+}
+
+impl<T: Callback, S: Sender<Event>> IdentifyProtocol<T, S> {
+    pub fn new(
+        callback: T,
+        identify_timeout: Duration,
+        event_sender: S,
+        compression: CompressionNegotiation,
+    ) -> Self {
+        IdentifyProtocol {
+            callback,
+            identify_timeout,
+            remote_infos: HashMap::new(),
+            event_sender,
+            compression,
+        }
+    }
+
+    fn send_event(&mut self, event: Event) {
+        if let Err(err) = self.event_sender.try_send(event) {
+            error!("send identify event error: {}", err);
+        }
+    }
+
+    fn encode(&mut self) -> Bytes {
+        let chain_id = self.callback.chain_id().to_vec();
+        let identify = self.callback.identify().to_vec();
+        let capability = if self.callback.supports_snappy() {
+            CAPABILITY_SNAPPY
+        } else {
+            0
+        };
+        let mut frame = Vec::with_capacity(5 + chain_id.len() + identify.len());
+        frame.extend_from_slice(&(chain_id.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&chain_id);
+        frame.push(capability);
+        frame.extend_from_slice(&identify);
+        Bytes::from(frame)
+    }
+}
+
+impl<T: Callback, S: Sender<Event>> ServiceProtocol for IdentifyProtocol<T, S> {
+    fn init(&mut self, context: &mut ProtocolContext) {
+        let proto_id = context.proto_id;
+        if context
+            .set_service_notify(proto_id, self.identify_timeout, CHECK_TIMEOUT_TOKEN)
+            .is_err()
+        {
+            warn!("start identify timeout check fail");
+        }
+    }
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let session = context.session;
+        let peer_id = match session.remote_pubkey {
+            Some(ref pubkey) => pubkey.peer_id(),
+            None => {
+                if context.disconnect(session.id).is_err() {
+                    debug!("disconnect fail");
+                }
+                return;
+            }
+        };
+        self.remote_infos.insert(
+            session.id,
+            RemoteInfo {
+                peer_id,
+                identified: false,
+                connected_at: Instant::now(),
+            },
+        );
+        let frame = self.encode();
+        if context.send_message(frame).is_err() {
+            debug!("send identify message fail");
+        }
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        let session_id = context.session.id;
+        self.remote_infos.remove(&session_id);
+        self.compression.session_closed(session_id);
+    }
+
+    fn received(&mut self, mut context: ProtocolContextMutRef, data: Bytes) {
+        let session_id = context.session.id;
+        let peer_id = match self.remote_infos.get(&session_id) {
+            Some(info) => info.peer_id.clone(),
+            None => return,
+        };
+
+        let (remote_chain_id, capability, payload) = match parse_frame(data.as_ref()) {
+            Some(parts) => parts,
+            None => {
+                if self.callback.misbehave(&peer_id, Misbehavior::InvalidData)
+                    == MisbehaveResult::Disconnect
+                    && context.disconnect(session_id).is_err()
+                {
+                    debug!("disconnect fail");
+                }
+                return;
+            }
+        };
+
+        if remote_chain_id != self.callback.chain_id()
+            || self.callback.verify_chain_id(remote_chain_id) == MisbehaveResult::Disconnect
+        {
+            warn!(
+                "peer {:?} is on a different network, disconnecting",
+                peer_id
+            );
+            self.callback
+                .misbehave(&peer_id, Misbehavior::DifferentChainId);
+            self.remote_infos.remove(&session_id);
+            if context.disconnect(session_id).is_err() {
+                debug!("disconnect fail");
+            }
+            self.send_event(Event::DifferentChainId(session_id));
+            return;
+        }
+
+        if self.callback.received_identify(&mut context, payload) == MisbehaveResult::Disconnect {
+            self.remote_infos.remove(&session_id);
+            if context.disconnect(session_id).is_err() {
+                debug!("disconnect fail");
+            }
+            return;
+        }
+
+        if let Some(info) = self.remote_infos.get_mut(&session_id) {
+            info.identified = true;
+        }
+        if advertises_snappy(capability) {
+            self.compression.mark_supports_snappy(session_id);
+        }
+        self.send_event(Event::Identified(session_id));
+    }
+
+    fn notify(&mut self, context: &mut ProtocolContext, token: u64) {
+        if token != CHECK_TIMEOUT_TOKEN {
+            return;
+        }
+        let timeout = self.identify_timeout;
+        let timed_out: Vec<(SessionId, PeerId)> = self
+            .remote_infos
+            .iter()
+            .filter(|(_, info)| !info.identified && info.connected_at.elapsed() >= timeout)
+            .map(|(session_id, info)| (*session_id, info.peer_id.clone()))
+            .collect();
+
+        for (session_id, peer_id) in timed_out {
+            self.remote_infos.remove(&session_id);
+            self.callback
+                .misbehave(&peer_id, Misbehavior::IdentifyTimeout);
+            if context.disconnect(session_id).is_err() {
+                debug!("disconnect fail");
+            }
+            self.send_event(Event::Timeout(session_id));
+        }
+    }
+}
+
+/// Splits a wire frame into `(chain_id, capability_byte, identify_payload)`. Returns `None`
+/// (rather than panicking) on a truncated or malformed frame.
+fn parse_frame(data: &[u8]) -> Option<(&[u8], u8, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (len_bytes, rest) = data.split_at(4);
+    let chain_id_len =
+        u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+    if rest.len() < chain_id_len + 1 {
+        return None;
+    }
+    let (chain_id, rest) = rest.split_at(chain_id_len);
+    let (capability, payload) = rest.split_at(1);
+    Some((chain_id, capability[0], payload))
+}
+
+/// Whether `capability` advertises Snappy support.
+fn advertises_snappy(capability: u8) -> bool {
+    capability & CAPABILITY_SNAPPY != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{advertises_snappy, parse_frame, CAPABILITY_SNAPPY};
+
+    #[test]
+    fn test_parse_frame_round_trip() {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&4u32.to_be_bytes());
+        frame.extend_from_slice(b"ckb1");
+        frame.push(CAPABILITY_SNAPPY);
+        frame.extend_from_slice(b"hello");
+        assert_eq!(
+            parse_frame(&frame),
+            Some((b"ckb1".as_ref(), CAPABILITY_SNAPPY, b"hello".as_ref()))
+        );
+    }
+
+    #[test]
+    fn test_parse_frame_truncated_is_none() {
+        assert_eq!(parse_frame(&[0, 0, 0]), None);
+        assert_eq!(parse_frame(&[0, 0, 0, 4, b'a', b'b', b'c', b'd']), None);
+    }
+
+    #[test]
+    fn test_advertises_snappy() {
+        assert!(advertises_snappy(CAPABILITY_SNAPPY));
+        assert!(!advertises_snappy(0));
+    }
+}
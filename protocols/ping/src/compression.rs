@@ -0,0 +1,112 @@
+/// Optional Snappy compression for protocol frames, borrowed from the compressed-payload
+/// approach devp2p sessions use. A single leading byte tags each frame as raw or compressed
+/// so peers that disagree on `Compression` still interoperate.
+use log::error;
+
+const FRAME_TAG_RAW: u8 = 0;
+const FRAME_TAG_SNAPPY: u8 = 1;
+
+/// Per-direction compression capability negotiated during the handshake.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Frames are sent and expected uncompressed.
+    None,
+    /// Frames at or above the configured threshold are Snappy-compressed.
+    Snappy,
+}
+
+/// A frame failed to decode: either the tag byte was unrecognized, the Snappy stream was
+/// corrupt, or the claimed decompressed size exceeded the configured cap (a decompression
+/// bomb guard). Callers should map this to `Event::UnexpectedError` rather than panicking
+/// or allocating unboundedly.
+#[derive(Debug)]
+pub struct FrameDecodeError;
+
+/// Tags `payload` with a frame header, compressing it with Snappy when `compression` is
+/// enabled and `payload` is at least `threshold` bytes.
+pub fn encode_frame(payload: &[u8], compression: Compression, threshold: usize) -> Vec<u8> {
+    if compression == Compression::Snappy && payload.len() >= threshold {
+        match snap::raw::Encoder::new().compress_vec(payload) {
+            Ok(compressed) => {
+                let mut framed = Vec::with_capacity(compressed.len() + 1);
+                framed.push(FRAME_TAG_SNAPPY);
+                framed.extend_from_slice(&compressed);
+                return framed;
+            }
+            Err(err) => error!("snappy compress error, falling back to raw frame: {}", err),
+        }
+    }
+    let mut framed = Vec::with_capacity(payload.len() + 1);
+    framed.push(FRAME_TAG_RAW);
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Strips the frame header from `data`, decompressing it if it was tagged as Snappy.
+/// `max_decompressed_size` caps the claimed decompressed length to guard against
+/// decompression bombs.
+pub fn decode_frame(
+    data: &[u8],
+    max_decompressed_size: usize,
+) -> Result<Vec<u8>, FrameDecodeError> {
+    match data.split_first() {
+        Some((&FRAME_TAG_RAW, rest)) => Ok(rest.to_vec()),
+        Some((&FRAME_TAG_SNAPPY, rest)) => {
+            let decompressed_len = snap::raw::decompress_len(rest).map_err(|_| FrameDecodeError)?;
+            if decompressed_len > max_decompressed_size {
+                return Err(FrameDecodeError);
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(rest)
+                .map_err(|_| FrameDecodeError)
+        }
+        _ => Err(FrameDecodeError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_raw_below_threshold() {
+        let payload = b"short ping payload";
+        let framed = encode_frame(payload, Compression::Snappy, 1024);
+        assert_eq!(framed[0], FRAME_TAG_RAW);
+        assert_eq!(decode_frame(&framed, 1024).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_round_trip_snappy_above_threshold() {
+        let payload = vec![b'a'; 2048];
+        let framed = encode_frame(&payload, Compression::Snappy, 1024);
+        assert_eq!(framed[0], FRAME_TAG_SNAPPY);
+        assert!(framed.len() < payload.len());
+        assert_eq!(decode_frame(&framed, payload.len() + 1).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_compression_disabled_never_compresses() {
+        let payload = vec![b'a'; 2048];
+        let framed = encode_frame(&payload, Compression::None, 1024);
+        assert_eq!(framed[0], FRAME_TAG_RAW);
+    }
+
+    #[test]
+    fn test_decompression_bomb_guard_rejects_oversized_claim() {
+        let payload = vec![b'a'; 2048];
+        let framed = encode_frame(&payload, Compression::Snappy, 1024);
+        assert!(decode_frame(&framed, 16).is_err());
+    }
+
+    #[test]
+    fn test_unknown_tag_is_an_error_not_a_panic() {
+        let framed = vec![0xff, 1, 2, 3];
+        assert!(decode_frame(&framed, 1024).is_err());
+    }
+
+    #[test]
+    fn test_empty_frame_is_an_error() {
+        assert!(decode_frame(&[], 1024).is_err());
+    }
+}
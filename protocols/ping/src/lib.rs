@@ -5,12 +5,17 @@ mod protocol_generated;
 #[allow(clippy::all)]
 #[allow(dead_code)]
 mod protocol_generated_verifier;
+mod compression;
 
+pub use compression::Compression;
+
+use crate::compression::{decode_frame, encode_frame};
 use crate::protocol_generated::p2p::ping::*;
 use bytes::Bytes;
 use flatbuffers::{FlatBufferBuilder, WIPOffset};
 use flatbuffers_verifier::get_root;
 use generic_channel::Sender;
+use identify::capability::CompressionNegotiation;
 use log::{debug, error, warn};
 use p2p::{
     context::{ProtocolContext, ProtocolContextMutRef},
@@ -28,6 +33,17 @@ use std::{
 const SEND_PING_TOKEN: u64 = 0;
 const CHECK_TIMEOUT_TOKEN: u64 = 1;
 
+/// Smoothing factor for the RTT exponentially-weighted moving average, `ema = ema*(1-ALPHA) + sample*ALPHA`.
+const DEFAULT_RTT_ALPHA: f64 = 0.2;
+/// Effective deadline is `max(min_timeout, RTT_K * ema)`.
+const DEFAULT_RTT_K: u32 = 6;
+/// Consecutive timeouts before a peer is considered unreliable and disconnected.
+const DEFAULT_MAX_CONSECUTIVE_TIMEOUTS: u32 = 3;
+/// Frames smaller than this are sent raw even when compression is enabled.
+const DEFAULT_COMPRESS_THRESHOLD: usize = 256;
+/// Upper bound on a frame's claimed decompressed size, as a decompression-bomb guard.
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024;
+
 /// Ping protocol events
 #[derive(Debug)]
 pub enum Event {
@@ -37,6 +53,8 @@ pub enum Event {
     Pong(PeerId, Duration),
     /// Peer is timeout.
     Timeout(PeerId),
+    /// Peer has timed out too many times in a row and has been disconnected.
+    TooManyTimeouts(PeerId),
     /// Peer cause a unexpected error.
     UnexpectedError(PeerId),
 }
@@ -44,10 +62,29 @@ pub enum Event {
 /// Ping protocol handler.
 ///
 /// The interval means that we send ping to peers.
-/// The timeout means that consider peer is timeout if during a timeout we still have not received pong from a peer
+/// The timeout means that we consider a peer timed out if, during the effective deadline,
+/// we still have not received a pong from it. The effective deadline adapts to the peer's
+/// measured RTT instead of using `min_timeout` as a flat cutoff; see [`PingStatus::deadline`].
 pub struct PingHandler<S: Sender<Event>> {
     interval: Duration,
-    timeout: Duration,
+    /// Floor for the adaptive deadline, and the deadline used until a peer's first pong.
+    min_timeout: Duration,
+    /// Multiplier applied to the RTT EMA to get the effective deadline.
+    k: u32,
+    /// Smoothing factor for the RTT EMA, in `(0, 1]`.
+    alpha: f64,
+    /// Consecutive timeouts before `Event::TooManyTimeouts` fires and the session is dropped.
+    max_consecutive_timeouts: u32,
+    /// Compression we'd like to use for outbound frames, if the peer has advertised support
+    /// for it during identify (see `negotiation`). Peers always accept raw frames.
+    compression: Compression,
+    /// Per-session Snappy support as negotiated during identify; `None` means compression
+    /// was never wired up, so frames are always sent raw regardless of `compression`.
+    negotiation: Option<CompressionNegotiation>,
+    /// Frames below this size are sent raw even when compression is negotiated.
+    compress_threshold: usize,
+    /// Cap on a frame's claimed decompressed size, guarding against decompression bombs.
+    max_decompressed_size: usize,
     connected_session_ids: HashMap<SessionId, PingStatus>,
     event_sender: S,
 }
@@ -56,12 +93,82 @@ impl<S: Sender<Event>> PingHandler<S> {
     pub fn new(interval: Duration, timeout: Duration, event_sender: S) -> PingHandler<S> {
         PingHandler {
             interval,
-            timeout,
+            min_timeout: timeout,
+            k: DEFAULT_RTT_K,
+            alpha: DEFAULT_RTT_ALPHA,
+            max_consecutive_timeouts: DEFAULT_MAX_CONSECUTIVE_TIMEOUTS,
+            compression: Compression::None,
+            negotiation: None,
+            compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
             connected_session_ids: Default::default(),
             event_sender,
         }
     }
 
+    /// Sets the compression we'd like to use for outbound frames above `compress_threshold`.
+    /// Has no effect until a [`CompressionNegotiation`] handle is also set via
+    /// `with_compression_negotiation`, since a session's peer must have advertised support
+    /// for it during identify before we'll actually compress frames for that session.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Shares the per-session Snappy capability negotiated by the identify protocol, so
+    /// `with_compression(Compression::Snappy)` only takes effect for sessions whose peer
+    /// advertised support for it.
+    pub fn with_compression_negotiation(mut self, negotiation: CompressionNegotiation) -> Self {
+        self.negotiation = Some(negotiation);
+        self
+    }
+
+    /// Sets the minimum payload size, in bytes, before a frame is compressed.
+    pub fn with_compress_threshold(mut self, compress_threshold: usize) -> Self {
+        self.compress_threshold = compress_threshold;
+        self
+    }
+
+    /// The compression to actually use for `session_id`: `self.compression` if (and only if)
+    /// its peer has advertised Snappy support during identify, `Compression::None` otherwise.
+    fn effective_compression(&self, session_id: SessionId) -> Compression {
+        match self.compression {
+            Compression::Snappy
+                if self
+                    .negotiation
+                    .as_ref()
+                    .map_or(false, |negotiation| negotiation.supports_snappy(session_id)) =>
+            {
+                Compression::Snappy
+            }
+            _ => Compression::None,
+        }
+    }
+
+    /// Sets the cap on a frame's claimed decompressed size.
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: usize) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// Sets the multiplier applied to the RTT EMA to compute the effective deadline.
+    pub fn with_k(mut self, k: u32) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Sets the smoothing factor used to update the RTT EMA on each pong.
+    pub fn with_alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Sets how many consecutive timeouts a peer may have before it is disconnected.
+    pub fn with_max_consecutive_timeouts(mut self, max_consecutive_timeouts: u32) -> Self {
+        self.max_consecutive_timeouts = max_consecutive_timeouts;
+        self
+    }
+
     pub fn send_event(&mut self, event: Event) {
         if let Err(err) = self.event_sender.try_send(event) {
             error!("send ping event error: {}", err);
@@ -77,6 +184,10 @@ struct PingStatus {
     /// The time we last send ping to this peer.
     last_ping: SystemTime,
     peer_id: PeerId,
+    /// Exponentially-weighted moving average of measured RTT, seeded on the first pong.
+    rtt_ema: Option<Duration>,
+    /// Number of timeouts seen in a row; resets to zero on any successful pong.
+    consecutive_timeouts: u32,
 }
 
 impl PingStatus {
@@ -92,6 +203,30 @@ impl PingStatus {
     fn elapsed(&self) -> Duration {
         self.last_ping.elapsed().unwrap_or(Duration::from_secs(0))
     }
+
+    /// Folds a fresh RTT sample into the EMA, seeding it on the first measurement.
+    ///
+    /// Degrades gracefully (never panics or produces NaN) for a zero sample, which is what
+    /// `elapsed()` returns if `SystemTime::elapsed` errors (e.g. clock went backwards).
+    fn record_rtt(&mut self, sample: Duration, alpha: f64) {
+        self.rtt_ema = Some(match self.rtt_ema {
+            None => sample,
+            Some(ema) => {
+                let ema_secs = ema.as_secs_f64() * (1.0 - alpha) + sample.as_secs_f64() * alpha;
+                Duration::from_secs_f64(ema_secs.max(0.0))
+            }
+        });
+        self.consecutive_timeouts = 0;
+    }
+
+    /// The effective deadline for this peer: `max(min_timeout, k * rtt_ema)`, falling back to
+    /// `min_timeout` until we have an RTT sample.
+    fn deadline(&self, min_timeout: Duration, k: u32) -> Duration {
+        match self.rtt_ema {
+            Some(ema) => std::cmp::max(min_timeout, ema * k),
+            None => min_timeout,
+        }
+    }
 }
 
 impl<S> ServiceProtocol for PingHandler<S>
@@ -108,7 +243,7 @@ where
             warn!("start ping fail");
         }
         if context
-            .set_service_notify(proto_id, self.timeout, CHECK_TIMEOUT_TOKEN)
+            .set_service_notify(proto_id, self.min_timeout, CHECK_TIMEOUT_TOKEN)
             .is_err()
         {
             warn!("start ping fail");
@@ -126,6 +261,8 @@ where
                         last_ping: SystemTime::now(),
                         processing: false,
                         peer_id,
+                        rtt_ema: None,
+                        consecutive_timeouts: 0,
                     });
                 debug!(
                     "proto id [{}] open on session [{}], address: [{}], type: [{:?}], version: {}",
@@ -157,7 +294,15 @@ where
             .get(&session.id)
             .map(|ps| ps.peer_id.clone())
         {
-            let msg = match get_root::<PingMessage>(data.as_ref()) {
+            let decompressed = match decode_frame(data.as_ref(), self.max_decompressed_size) {
+                Ok(decompressed) => decompressed,
+                Err(_) => {
+                    error!("decode frame error (bad tag or decompression bomb guard)");
+                    self.send_event(Event::UnexpectedError(peer_id));
+                    return;
+                }
+            };
+            let msg = match get_root::<PingMessage>(&decompressed) {
                 Ok(msg) => msg,
                 Err(e) => {
                     error!("decode message error: {:?}", e);
@@ -171,10 +316,12 @@ where
                     let mut fbb = FlatBufferBuilder::new();
                     let msg = PingMessage::build_pong(&mut fbb, ping_msg.nonce());
                     fbb.finish(msg, None);
-                    if context
-                        .send_message(Bytes::from(fbb.finished_data()))
-                        .is_err()
-                    {
+                    let framed = encode_frame(
+                        fbb.finished_data(),
+                        self.effective_compression(session.id),
+                        self.compress_threshold,
+                    );
+                    if context.send_message(Bytes::from(framed)).is_err() {
                         debug!("send message fail");
                     }
                     self.send_event(Event::Ping(peer_id));
@@ -188,10 +335,13 @@ where
                         .map(|ps| (ps.processing, ps.nonce()))
                         == Some((true, pong_msg.nonce()))
                     {
+                        let alpha = self.alpha;
                         let ping_time = match self.connected_session_ids.get_mut(&session.id) {
                             Some(ps) => {
                                 ps.processing = false;
-                                ps.elapsed()
+                                let ping_time = ps.elapsed();
+                                ps.record_rtt(ping_time, alpha);
+                                ping_time
                             }
                             None => return,
                         };
@@ -231,34 +381,85 @@ where
                     let mut fbb = FlatBufferBuilder::new();
                     let msg = PingMessage::build_ping(&mut fbb, peers[0].1);
                     fbb.finish(msg, None);
-                    let peer_ids: Vec<SessionId> = peers
+                    let proto_id = context.proto_id;
+                    // Each session may have negotiated compression independently during
+                    // identify, so the broadcast can't reuse a single encoded frame for
+                    // everyone; split peers by effective compression and send one frame per
+                    // group instead.
+                    let (snappy_peers, raw_peers): (Vec<SessionId>, Vec<SessionId>) = peers
                         .into_iter()
                         .map(|(session_id, _)| session_id)
-                        .collect();
-                    let proto_id = context.proto_id;
-                    if context
-                        .filter_broadcast(
-                            TargetSession::Multi(peer_ids),
-                            proto_id,
-                            Bytes::from(fbb.finished_data()),
-                        )
-                        .is_err()
-                    {
-                        debug!("send message fail");
+                        .partition(|session_id| {
+                            self.effective_compression(*session_id) == Compression::Snappy
+                        });
+                    if !snappy_peers.is_empty() {
+                        let framed = encode_frame(
+                            fbb.finished_data(),
+                            Compression::Snappy,
+                            self.compress_threshold,
+                        );
+                        if context
+                            .filter_broadcast(
+                                TargetSession::Multi(snappy_peers),
+                                proto_id,
+                                Bytes::from(framed),
+                            )
+                            .is_err()
+                        {
+                            debug!("send message fail");
+                        }
+                    }
+                    if !raw_peers.is_empty() {
+                        let framed = encode_frame(
+                            fbb.finished_data(),
+                            Compression::None,
+                            self.compress_threshold,
+                        );
+                        if context
+                            .filter_broadcast(
+                                TargetSession::Multi(raw_peers),
+                                proto_id,
+                                Bytes::from(framed),
+                            )
+                            .is_err()
+                        {
+                            debug!("send message fail");
+                        }
                     }
                 }
             }
             CHECK_TIMEOUT_TOKEN => {
                 debug!("proto [{}] check ping timeout", context.proto_id);
-                let timeout = self.timeout;
-                for peer_id in self
+                let min_timeout = self.min_timeout;
+                let k = self.k;
+                let max_consecutive_timeouts = self.max_consecutive_timeouts;
+                let timed_out_sessions: Vec<SessionId> = self
                     .connected_session_ids
-                    .values()
-                    .filter(|ps| ps.processing && ps.elapsed() >= timeout)
-                    .map(|ps| ps.peer_id.clone())
-                    .collect::<Vec<PeerId>>()
-                {
-                    self.send_event(Event::Timeout(peer_id));
+                    .iter()
+                    .filter(|(_, ps)| ps.processing && ps.elapsed() >= ps.deadline(min_timeout, k))
+                    .map(|(session_id, _)| *session_id)
+                    .collect();
+
+                for session_id in timed_out_sessions {
+                    let (peer_id, too_many) = match self.connected_session_ids.get_mut(&session_id)
+                    {
+                        Some(ps) => {
+                            ps.consecutive_timeouts += 1;
+                            (
+                                ps.peer_id.clone(),
+                                ps.consecutive_timeouts >= max_consecutive_timeouts,
+                            )
+                        }
+                        None => continue,
+                    };
+                    self.send_event(Event::Timeout(peer_id.clone()));
+                    if too_many {
+                        self.connected_session_ids.remove(&session_id);
+                        if context.disconnect(session_id).is_err() {
+                            debug!("disconnect fail");
+                        }
+                        self.send_event(Event::TooManyTimeouts(peer_id));
+                    }
                 }
             }
             _ => panic!("unknown token {}", token),
@@ -297,3 +498,91 @@ impl<'a> PingMessage<'a> {
         builder.finish()
     }
 }
+
+// These exercise `PingStatus`'s pure logic directly. The `notify()` dispatch that drives it
+// (the `CHECK_TIMEOUT_TOKEN` branch firing `Event::TooManyTimeouts`) isn't covered here since
+// that needs a real `ProtocolContext`, which is opaque to this trimmed source tree.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> PingStatus {
+        PingStatus {
+            processing: true,
+            last_ping: SystemTime::now(),
+            peer_id: PeerId::random(),
+            rtt_ema: None,
+            consecutive_timeouts: 0,
+        }
+    }
+
+    #[test]
+    fn test_record_rtt_seeds_ema_on_first_sample() {
+        let mut status = status();
+        status.record_rtt(Duration::from_millis(100), DEFAULT_RTT_ALPHA);
+        assert_eq!(status.rtt_ema, Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_record_rtt_degrades_gracefully_on_zero_sample() {
+        let mut status = status();
+        status.consecutive_timeouts = 2;
+        status.record_rtt(Duration::from_secs(0), DEFAULT_RTT_ALPHA);
+        assert_eq!(status.rtt_ema, Some(Duration::from_secs(0)));
+        assert_eq!(status.consecutive_timeouts, 0);
+    }
+
+    #[test]
+    fn test_record_rtt_converges_towards_new_samples() {
+        let mut status = status();
+        status.record_rtt(Duration::from_millis(100), 0.5);
+        status.record_rtt(Duration::from_millis(300), 0.5);
+        assert_eq!(status.rtt_ema, Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_deadline_falls_back_to_min_timeout_without_a_sample() {
+        let status = status();
+        assert_eq!(
+            status.deadline(Duration::from_secs(5), DEFAULT_RTT_K),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn test_deadline_scales_with_rtt_once_it_exceeds_min_timeout() {
+        let mut status = status();
+        status.record_rtt(Duration::from_secs(1), DEFAULT_RTT_ALPHA);
+        assert_eq!(
+            status.deadline(Duration::from_secs(1), DEFAULT_RTT_K),
+            Duration::from_secs(1) * DEFAULT_RTT_K
+        );
+    }
+
+    #[test]
+    fn test_deadline_never_drops_below_min_timeout() {
+        let mut status = status();
+        status.record_rtt(Duration::from_millis(1), DEFAULT_RTT_ALPHA);
+        assert_eq!(
+            status.deadline(Duration::from_secs(10), DEFAULT_RTT_K),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_consecutive_timeouts_cross_the_disconnect_threshold() {
+        // Mirrors `notify`'s `CHECK_TIMEOUT_TOKEN` branch: each miss increments
+        // `consecutive_timeouts`, and a pong resets it; crossing
+        // `DEFAULT_MAX_CONSECUTIVE_TIMEOUTS` is what triggers `Event::TooManyTimeouts`.
+        let mut status = status();
+        for _ in 0..DEFAULT_MAX_CONSECUTIVE_TIMEOUTS - 1 {
+            status.consecutive_timeouts += 1;
+            assert!(status.consecutive_timeouts < DEFAULT_MAX_CONSECUTIVE_TIMEOUTS);
+        }
+        status.consecutive_timeouts += 1;
+        assert!(status.consecutive_timeouts >= DEFAULT_MAX_CONSECUTIVE_TIMEOUTS);
+
+        status.record_rtt(Duration::from_millis(50), DEFAULT_RTT_ALPHA);
+        assert_eq!(status.consecutive_timeouts, 0);
+    }
+}
@@ -0,0 +1,297 @@
+/// Offloads the expensive parts of a SECIO handshake (`generate_agreement`/`agree`) and bulk
+/// frame encryption/decryption onto a small crossbeam-channel worker pool, so a burst of
+/// simultaneous dials doesn't serialize that work onto the connection's own task.
+///
+/// Ordering within a session is preserved because each submitted job gets its own reply
+/// channel: callers that submit job N before job N+1 and receive in that same order always
+/// see N's result first, even though the workers themselves may finish jobs out of order.
+///
+/// Follow-up: nothing in this source tree constructs a `CryptoExecutor` or calls into it —
+/// the real SECIO handshake/connection-loop type that would own one isn't part of this
+/// trimmed tree (only `secio/src/exchange.rs` and `secio/src/rotation.rs` are, alongside
+/// this file). Wiring
+/// `CryptoExecutor::generate_agreement`/`agree` into the real handshake path is left as a
+/// follow-up for whoever has that code.
+use std::thread;
+
+use crossbeam_channel::{bounded, unbounded, Sender};
+use ring::agreement;
+
+use crate::error::SecioError;
+use crate::exchange::{agree, generate_agreement, KeyAgreement};
+
+enum CryptoJob {
+    GenerateAgreement {
+        algorithm: KeyAgreement,
+        reply: Sender<Result<(agreement::EphemeralPrivateKey, Vec<u8>), SecioError>>,
+    },
+    Agree {
+        algorithm: KeyAgreement,
+        private_key: agreement::EphemeralPrivateKey,
+        other_public_key: Vec<u8>,
+        reply: Sender<Result<Vec<u8>, SecioError>>,
+    },
+    Cipher {
+        job: Box<dyn FnOnce() -> Vec<u8> + Send>,
+        reply: Sender<Vec<u8>>,
+    },
+}
+
+/// A pool of crypto worker threads that SECIO handshakes and frame ciphers can be
+/// offloaded to. Sized to `num_cpus` by convention; see [`CryptoPool::new`].
+pub struct CryptoPool {
+    job_sender: Sender<CryptoJob>,
+    size: usize,
+}
+
+impl CryptoPool {
+    /// Spawns `size` worker threads pulling jobs from a shared unbounded queue.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0: a pool with no workers would drop the job queue's only receiver
+    /// before this returns, so every later `generate_agreement`/`agree`/`submit_cipher_job`
+    /// call would panic on the send instead of behaving like a disabled pool. Use
+    /// `CryptoExecutor::inline()` to run without a pool.
+    pub fn new(size: usize) -> Self {
+        assert!(
+            size > 0,
+            "CryptoPool size must be at least 1; use CryptoExecutor::inline() to disable the pool"
+        );
+        let (job_sender, job_receiver) = unbounded::<CryptoJob>();
+        for idx in 0..size {
+            let job_receiver = job_receiver.clone();
+            thread::Builder::new()
+                .name(format!("secio-crypto-{}", idx))
+                .spawn(move || {
+                    for job in job_receiver {
+                        match job {
+                            CryptoJob::GenerateAgreement { algorithm, reply } => {
+                                let _ = reply.send(generate_agreement(algorithm));
+                            }
+                            CryptoJob::Agree {
+                                algorithm,
+                                private_key,
+                                other_public_key,
+                                reply,
+                            } => {
+                                let _ = reply.send(agree(
+                                    algorithm,
+                                    private_key,
+                                    &other_public_key,
+                                    32,
+                                ));
+                            }
+                            CryptoJob::Cipher { job, reply } => {
+                                let _ = reply.send(job());
+                            }
+                        }
+                    }
+                })
+                .expect("failed to spawn secio crypto worker");
+        }
+        CryptoPool { job_sender, size }
+    }
+
+    /// Number of worker threads in this pool.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Runs `generate_agreement` on a worker thread and blocks for the result.
+    pub fn generate_agreement(
+        &self,
+        algorithm: KeyAgreement,
+    ) -> Result<(agreement::EphemeralPrivateKey, Vec<u8>), SecioError> {
+        let (reply, rx) = bounded(1);
+        self.job_sender
+            .send(CryptoJob::GenerateAgreement { algorithm, reply })
+            .expect("crypto pool workers gone");
+        rx.recv().expect("crypto pool worker dropped reply channel")
+    }
+
+    /// Runs `agree` on a worker thread and blocks for the result.
+    pub fn agree(
+        &self,
+        algorithm: KeyAgreement,
+        private_key: agreement::EphemeralPrivateKey,
+        other_public_key: Vec<u8>,
+    ) -> Result<Vec<u8>, SecioError> {
+        let (reply, rx) = bounded(1);
+        self.job_sender
+            .send(CryptoJob::Agree {
+                algorithm,
+                private_key,
+                other_public_key,
+                reply,
+            })
+            .expect("crypto pool workers gone");
+        rx.recv().expect("crypto pool worker dropped reply channel")
+    }
+
+    /// Submits a bulk encrypt/decrypt job and returns a handle the caller can `recv()` on
+    /// once the result is needed. Submitting jobs in order and receiving them in that same
+    /// order preserves per-session frame order regardless of which worker finishes first.
+    pub fn submit_cipher_job<F>(&self, job: F) -> CipherJobHandle
+    where
+        F: FnOnce() -> Vec<u8> + Send + 'static,
+    {
+        let (reply, rx) = bounded(1);
+        self.job_sender
+            .send(CryptoJob::Cipher {
+                job: Box::new(job),
+                reply,
+            })
+            .expect("crypto pool workers gone");
+        CipherJobHandle { rx }
+    }
+}
+
+/// A pending bulk cipher job submitted to a [`CryptoPool`].
+pub struct CipherJobHandle {
+    rx: crossbeam_channel::Receiver<Vec<u8>>,
+}
+
+impl CipherJobHandle {
+    /// Blocks until this job's worker has produced a result.
+    pub fn recv(self) -> Vec<u8> {
+        self.rx
+            .recv()
+            .expect("crypto pool worker dropped reply channel")
+    }
+}
+
+/// Dispatches SECIO crypto work either to a [`CryptoPool`] or inline on the calling thread,
+/// so single-connection latency is unaffected when the pool is disabled.
+pub enum CryptoExecutor {
+    /// Run everything inline; the default when the pool is not enabled.
+    Inline,
+    /// Offload to a worker pool.
+    Pool(CryptoPool),
+}
+
+impl CryptoExecutor {
+    /// The default executor: runs everything inline, matching prior behavior.
+    pub fn inline() -> Self {
+        CryptoExecutor::Inline
+    }
+
+    /// Enables the crypto pool with `size` worker threads (typically `num_cpus::get()`).
+    pub fn with_pool(size: usize) -> Self {
+        CryptoExecutor::Pool(CryptoPool::new(size))
+    }
+
+    pub fn generate_agreement(
+        &self,
+        algorithm: KeyAgreement,
+    ) -> Result<(agreement::EphemeralPrivateKey, Vec<u8>), SecioError> {
+        match self {
+            CryptoExecutor::Inline => generate_agreement(algorithm),
+            CryptoExecutor::Pool(pool) => pool.generate_agreement(algorithm),
+        }
+    }
+
+    pub fn agree(
+        &self,
+        algorithm: KeyAgreement,
+        private_key: agreement::EphemeralPrivateKey,
+        other_public_key: Vec<u8>,
+    ) -> Result<Vec<u8>, SecioError> {
+        match self {
+            CryptoExecutor::Inline => agree(algorithm, private_key, &other_public_key, 32),
+            CryptoExecutor::Pool(pool) => pool.agree(algorithm, private_key, other_public_key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parallel_jobs_use_multiple_workers() {
+        let pool = CryptoPool::new(4);
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let active = active.clone();
+                let max_active = max_active.clone();
+                let handle = pool.submit_cipher_job(move || {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    vec![0]
+                });
+                handle
+            })
+            .collect();
+
+        for handle in handles {
+            handle.recv();
+        }
+
+        assert!(
+            max_active.load(Ordering::SeqCst) > 1,
+            "expected more than one job to run concurrently across workers"
+        );
+    }
+
+    #[test]
+    fn test_frame_order_preserved_within_session() {
+        let pool = CryptoPool::new(4);
+        // Jobs finish in reverse order of submission (later ones sleep less), but the
+        // caller still recv()s them in submission order, so the session sees frames 0..N.
+        let handles: Vec<_> = (0u8..10)
+            .map(|i| {
+                pool.submit_cipher_job(move || {
+                    thread::sleep(Duration::from_millis(u64::from(10 - i)));
+                    vec![i]
+                })
+            })
+            .collect();
+
+        let received: Vec<u8> = handles.into_iter().map(|h| h.recv()[0]).collect();
+        assert_eq!(received, (0u8..10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_pool_generate_agreement_matches_inline() {
+        let pool = CryptoPool::new(2);
+        let (priv_a, pub_a) = pool.generate_agreement(KeyAgreement::X25519).unwrap();
+        let (priv_b, pub_b) = generate_agreement(KeyAgreement::X25519).unwrap();
+
+        let shared_a = pool.agree(KeyAgreement::X25519, priv_a, pub_b).unwrap();
+        let shared_b = agree(KeyAgreement::X25519, priv_b, &pub_a, 32).unwrap();
+        assert_eq!(shared_a, shared_b);
+    }
+
+    #[test]
+    #[should_panic(expected = "CryptoPool size must be at least 1")]
+    fn test_zero_size_pool_panics_up_front() {
+        CryptoPool::new(0);
+    }
+
+    #[test]
+    fn test_inline_executor_matches_pool_executor() {
+        let inline = CryptoExecutor::inline();
+        let pooled = CryptoExecutor::with_pool(2);
+
+        let (priv_inline, pub_inline) = inline.generate_agreement(KeyAgreement::EcdhP256).unwrap();
+        let (priv_pooled, pub_pooled) = pooled.generate_agreement(KeyAgreement::EcdhP256).unwrap();
+
+        let shared_inline = inline
+            .agree(KeyAgreement::EcdhP256, priv_inline, pub_pooled)
+            .unwrap();
+        let shared_pooled = pooled
+            .agree(KeyAgreement::EcdhP256, priv_pooled, pub_inline)
+            .unwrap();
+        assert_eq!(shared_inline, shared_pooled);
+    }
+}
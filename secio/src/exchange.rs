@@ -11,6 +11,7 @@ use crate::error::SecioError;
 pub enum KeyAgreement {
     EcdhP256,
     EcdhP384,
+    X25519,
 }
 
 impl Into<&'static agreement::Algorithm> for KeyAgreement {
@@ -19,6 +20,34 @@ impl Into<&'static agreement::Algorithm> for KeyAgreement {
         match self {
             KeyAgreement::EcdhP256 => &agreement::ECDH_P256,
             KeyAgreement::EcdhP384 => &agreement::ECDH_P384,
+            KeyAgreement::X25519 => &agreement::X25519,
+        }
+    }
+}
+
+impl KeyAgreement {
+    /// The name used to negotiate this algorithm on the wire.
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyAgreement::EcdhP256 => "P-256",
+            KeyAgreement::EcdhP384 => "P-384",
+            KeyAgreement::X25519 => "X25519",
+        }
+    }
+
+    /// Looks up a `KeyAgreement` from the name negotiated on the wire.
+    ///
+    /// Returns `SecioError::EphemeralKeyGenerationFailed` instead of panicking when the peer
+    /// proposes a curve we don't know about.
+    pub fn from_name(name: &str) -> Result<Self, SecioError> {
+        match name {
+            "P-256" => Ok(KeyAgreement::EcdhP256),
+            "P-384" => Ok(KeyAgreement::EcdhP384),
+            "X25519" => Ok(KeyAgreement::X25519),
+            _ => {
+                debug!("unsupported key agreement algorithm: {}", name);
+                Err(SecioError::EphemeralKeyGenerationFailed)
+            }
         }
     }
 }
@@ -59,3 +88,49 @@ pub fn agree(
         |key_material| Ok(key_material.to_vec()),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{agree, generate_agreement, KeyAgreement};
+
+    fn test_agree(algorithm: KeyAgreement) {
+        let (my_priv_key, my_pub_key) = generate_agreement(algorithm).unwrap();
+        let (other_priv_key, other_pub_key) = generate_agreement(algorithm).unwrap();
+
+        let my_shared = agree(algorithm, my_priv_key, &other_pub_key, 32).unwrap();
+        let other_shared = agree(algorithm, other_priv_key, &my_pub_key, 32).unwrap();
+
+        assert_eq!(my_shared, other_shared);
+    }
+
+    #[test]
+    fn test_agree_ecdh_p256() {
+        test_agree(KeyAgreement::EcdhP256);
+    }
+
+    #[test]
+    fn test_agree_ecdh_p384() {
+        test_agree(KeyAgreement::EcdhP384);
+    }
+
+    #[test]
+    fn test_agree_x25519() {
+        test_agree(KeyAgreement::X25519);
+    }
+
+    #[test]
+    fn test_from_name_round_trip() {
+        for algorithm in &[
+            KeyAgreement::EcdhP256,
+            KeyAgreement::EcdhP384,
+            KeyAgreement::X25519,
+        ] {
+            assert_eq!(KeyAgreement::from_name(algorithm.name()).unwrap(), *algorithm);
+        }
+    }
+
+    #[test]
+    fn test_from_name_unsupported() {
+        assert!(KeyAgreement::from_name("Curve25519-unknown").is_err());
+    }
+}
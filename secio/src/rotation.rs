@@ -0,0 +1,325 @@
+/// Periodic ephemeral re-keying for long-lived SECIO sessions, modeled on WireGuard-style
+/// key rotation: the initiator periodically re-runs ECDH over the already-established
+/// secure channel and both sides switch to the new shared secret once it has been confirmed.
+///
+/// This module is freestanding: nothing in this source tree constructs a `RotationState`,
+/// calls `every_second`/`record_bytes_encrypted`, or sends the rekey frame it builds,
+/// because the `SecureStream`/connection-loop type that would own one isn't part of this
+/// source tree (only `secio/src/exchange.rs` and `secio/src/crypto_pool.rs` are, alongside
+/// this file). A real integration
+/// on a type shaped like tentacle's `SecureStream` would look like:
+///
+/// ```ignore
+/// // On every outbound frame, after encrypting it under `rotation.current_key()`:
+/// self.rotation.record_bytes_encrypted(frame.len());
+///
+/// // On the session's periodic tick (the same role PingHandler's CHECK_TIMEOUT_TOKEN plays):
+/// self.rotation.every_second();
+/// if self.rotation.should_rotate() {
+///     let public_key = self.rotation.begin_rotation()?;
+///     self.send_control_frame(build_rekey_frame(&public_key));
+/// }
+///
+/// // On receiving a control frame from the peer:
+/// if let Some(peer_public_key) = parse_rekey_frame(&frame) {
+///     // `complete_rotation` fails without a pending rotation, so the responder first
+///     // starts its own and answers with its fresh public key.
+///     if self.rotation.pending_public_key().is_none() {
+///         let public_key = self.rotation.begin_rotation()?;
+///         self.send_control_frame(build_rekey_frame(&public_key));
+///     }
+///     if self.rotation.complete_rotation(peer_public_key).is_err() {
+///         return Err(SecioError::SecretGenerationFailed); // tear the session down
+///     }
+/// }
+///
+/// // On decrypting an inbound frame, try the current key first and fall back to the
+/// // previous one while it's still valid, to ride out the overlap window:
+/// decrypt(frame, self.rotation.key_for_decrypt(false))
+///     .or_else(|_| decrypt(frame, self.rotation.key_for_decrypt(true).ok_or(())?))
+/// ```
+///
+/// Follow-up: wiring this into the real `SecureStream`/connection-loop type, once it's part
+/// of this tree, is left as a follow-up task for whoever has that code.
+use std::time::{Duration, Instant};
+
+use ring::agreement;
+
+use crate::error::SecioError;
+use crate::exchange::{agree, generate_agreement, KeyAgreement};
+
+/// Default overlap window during which the previous shared key is still accepted, so
+/// frames already in flight when rotation completes can still be decrypted.
+pub const DEFAULT_OVERLAP_WINDOW: Duration = Duration::from_secs(5);
+
+/// Wire tag for the dedicated rekey control frame: the rotating side sends its fresh
+/// ephemeral public key tagged with this byte (over the *current*, not-yet-rotated secure
+/// channel) so the receiver can tell it apart from ordinary application frames.
+pub const REKEY_FRAME_TAG: u8 = 0xfe;
+
+/// Builds the rekey control frame carrying `public_key`. See the module docs for where a
+/// real `SecureStream` would send this.
+pub fn build_rekey_frame(public_key: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(public_key.len() + 1);
+    frame.push(REKEY_FRAME_TAG);
+    frame.extend_from_slice(public_key);
+    frame
+}
+
+/// Parses a rekey control frame built by [`build_rekey_frame`]. Returns `None` rather than
+/// panicking if `frame` isn't one (e.g. it's an ordinary application frame).
+pub fn parse_rekey_frame(frame: &[u8]) -> Option<&[u8]> {
+    match frame.split_first() {
+        Some((&REKEY_FRAME_TAG, rest)) => Some(rest),
+        _ => None,
+    }
+}
+
+/// A rotation that has been started but not yet confirmed by the peer.
+struct PendingRotation {
+    private_key: agreement::EphemeralPrivateKey,
+    public_key: Vec<u8>,
+    started_at: Instant,
+}
+
+/// The key the session used before the most recent rotation completed.
+///
+/// Kept around for `overlap_window` so in-flight frames encrypted under it still decrypt.
+struct PreviousKey {
+    key: Vec<u8>,
+    retired_at: Instant,
+}
+
+/// Per-session rotation state: the current shared key, an in-flight rotation (if any),
+/// and the bookkeeping needed to decide when to rotate again.
+pub struct RotationState {
+    algorithm: KeyAgreement,
+    current_key: Vec<u8>,
+    previous_key: Option<PreviousKey>,
+    pending: Option<PendingRotation>,
+    rotate_counter: u64,
+    last_rotation: Instant,
+    bytes_since_rotation: u64,
+    rekey_after: Duration,
+    rekey_after_bytes: u64,
+    overlap_window: Duration,
+}
+
+impl RotationState {
+    /// Creates rotation state around the shared key derived during the initial handshake.
+    pub fn new(
+        algorithm: KeyAgreement,
+        initial_key: Vec<u8>,
+        rekey_after: Duration,
+        rekey_after_bytes: u64,
+    ) -> Self {
+        RotationState {
+            algorithm,
+            current_key: initial_key,
+            previous_key: None,
+            pending: None,
+            rotate_counter: 0,
+            last_rotation: Instant::now(),
+            bytes_since_rotation: 0,
+            rekey_after,
+            rekey_after_bytes,
+            overlap_window: DEFAULT_OVERLAP_WINDOW,
+        }
+    }
+
+    /// The shared key new frames should be encrypted with.
+    pub fn current_key(&self) -> &[u8] {
+        &self.current_key
+    }
+
+    /// How many rotations have completed on this session.
+    pub fn rotate_counter(&self) -> u64 {
+        self.rotate_counter
+    }
+
+    /// Call once per tick (mirrors `PingHandler`'s `every_second`-style notify) to age the
+    /// overlap window and drop the previous key once it has expired.
+    pub fn every_second(&mut self) {
+        if let Some(previous) = &self.previous_key {
+            if previous.retired_at.elapsed() >= self.overlap_window {
+                self.previous_key = None;
+            }
+        }
+    }
+
+    /// Accounts for bytes encrypted under the current key, for the byte-threshold trigger.
+    pub fn record_bytes_encrypted(&mut self, len: usize) {
+        self.bytes_since_rotation += len as u64;
+    }
+
+    /// Whether the initiator should start a new rotation: elapsed time exceeds
+    /// `rekey_after` or bytes encrypted exceed `rekey_after_bytes`, and none is already
+    /// in flight (only one rotation may be pending at a time).
+    pub fn should_rotate(&self) -> bool {
+        if self.pending.is_some() {
+            return false;
+        }
+        self.last_rotation.elapsed() >= self.rekey_after
+            || self.bytes_since_rotation >= self.rekey_after_bytes
+    }
+
+    /// Starts a rotation: generates a fresh ephemeral key pair and returns the public key
+    /// to send to the peer in a rekey control frame. Guarded by the pending slot so only
+    /// one rotation can be in flight at a time.
+    pub fn begin_rotation(&mut self) -> Result<Vec<u8>, SecioError> {
+        if self.pending.is_some() {
+            return Err(SecioError::SecretGenerationFailed);
+        }
+        let (private_key, public_key) = generate_agreement(self.algorithm)?;
+        let to_send = public_key.clone();
+        self.pending = Some(PendingRotation {
+            private_key,
+            public_key,
+            started_at: Instant::now(),
+        });
+        Ok(to_send)
+    }
+
+    /// Completes a rotation once the peer's ephemeral public key for this round has
+    /// arrived: computes the new shared secret, retires the old key into the overlap
+    /// window, and atomically switches the session over to the new one.
+    ///
+    /// A rejected or garbage peer public key must tear down the session rather than
+    /// silently keep using the stale key, so this returns `Err(SecioError)` in that case
+    /// and leaves the pending rotation cleared (the caller is expected to disconnect).
+    pub fn complete_rotation(&mut self, peer_public_key: &[u8]) -> Result<(), SecioError> {
+        let pending = self
+            .pending
+            .take()
+            .ok_or(SecioError::SecretGenerationFailed)?;
+        let new_key = agree(self.algorithm, pending.private_key, peer_public_key, 32)?;
+
+        self.previous_key = Some(PreviousKey {
+            key: std::mem::replace(&mut self.current_key, new_key),
+            retired_at: Instant::now(),
+        });
+        self.rotate_counter += 1;
+        self.last_rotation = Instant::now();
+        self.bytes_since_rotation = 0;
+        Ok(())
+    }
+
+    /// The public key of the in-flight rotation, if any (for retransmitting the rekey frame).
+    pub fn pending_public_key(&self) -> Option<&[u8]> {
+        self.pending.as_ref().map(|p| p.public_key.as_slice())
+    }
+
+    /// How long the current rotation has been pending, used to detect a peer that never acks.
+    pub fn pending_elapsed(&self) -> Option<Duration> {
+        self.pending.as_ref().map(|p| p.started_at.elapsed())
+    }
+
+    /// Drops the previous key immediately, e.g. once an ack confirms the peer switched over.
+    pub fn acknowledge_rotation(&mut self) {
+        self.previous_key = None;
+    }
+
+    /// The key to try when decrypting/verifying a frame: the current key, or with
+    /// `use_previous` the previous key, until `every_second` drops it after the overlap
+    /// window or `acknowledge_rotation` drops it (`None` once dropped).
+    pub fn key_for_decrypt(&self, use_previous: bool) -> Option<&[u8]> {
+        if use_previous {
+            self.previous_key.as_ref().map(|p| p.key.as_slice())
+        } else {
+            Some(&self.current_key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paired_state() -> (RotationState, RotationState) {
+        let (priv_a, pub_a) = generate_agreement(KeyAgreement::X25519).unwrap();
+        let (priv_b, pub_b) = generate_agreement(KeyAgreement::X25519).unwrap();
+        let shared = agree(KeyAgreement::X25519, priv_a, &pub_b, 32).unwrap();
+        let shared_other = agree(KeyAgreement::X25519, priv_b, &pub_a, 32).unwrap();
+        assert_eq!(shared, shared_other);
+
+        let initiator = RotationState::new(
+            KeyAgreement::X25519,
+            shared.clone(),
+            Duration::from_secs(3600),
+            u64::max_value(),
+        );
+        let responder = RotationState::new(
+            KeyAgreement::X25519,
+            shared,
+            Duration::from_secs(3600),
+            u64::max_value(),
+        );
+        (initiator, responder)
+    }
+
+    #[test]
+    fn test_should_rotate_on_byte_threshold() {
+        let mut state = RotationState::new(
+            KeyAgreement::X25519,
+            vec![0u8; 32],
+            Duration::from_secs(3600),
+            1024,
+        );
+        assert!(!state.should_rotate());
+        state.record_bytes_encrypted(2048);
+        assert!(state.should_rotate());
+    }
+
+    #[test]
+    fn test_only_one_rotation_in_flight() {
+        let (mut initiator, _) = paired_state();
+        assert!(initiator.begin_rotation().is_ok());
+        assert!(initiator.begin_rotation().is_err());
+    }
+
+    #[test]
+    fn test_rotation_switches_key_and_keeps_previous_during_overlap() {
+        let (mut initiator, mut responder) = paired_state();
+        let old_key = initiator.current_key().to_vec();
+
+        let initiator_pub = initiator.begin_rotation().unwrap();
+        let responder_pub = responder.begin_rotation().unwrap();
+
+        initiator.complete_rotation(&responder_pub).unwrap();
+        responder.complete_rotation(&initiator_pub).unwrap();
+
+        assert_eq!(initiator.current_key(), responder.current_key());
+        assert_ne!(initiator.current_key(), old_key.as_slice());
+        assert_eq!(initiator.rotate_counter(), 1);
+        assert_eq!(initiator.key_for_decrypt(true), Some(old_key.as_slice()));
+
+        initiator.acknowledge_rotation();
+        assert_eq!(initiator.key_for_decrypt(true), None);
+    }
+
+    #[test]
+    fn test_garbage_peer_key_fails_rotation() {
+        let (mut initiator, _) = paired_state();
+        initiator.begin_rotation().unwrap();
+        assert!(initiator.complete_rotation(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_complete_without_pending_fails() {
+        let (mut initiator, _) = paired_state();
+        assert!(initiator.complete_rotation(&[0u8; 32]).is_err());
+    }
+
+    #[test]
+    fn test_rekey_frame_round_trip() {
+        let (_, public_key) = generate_agreement(KeyAgreement::X25519).unwrap();
+        let frame = build_rekey_frame(&public_key);
+        assert_eq!(parse_rekey_frame(&frame), Some(public_key.as_slice()));
+    }
+
+    #[test]
+    fn test_rekey_frame_rejects_other_frames() {
+        assert_eq!(parse_rekey_frame(&[0x00, 1, 2, 3]), None);
+        assert_eq!(parse_rekey_frame(&[]), None);
+    }
+}